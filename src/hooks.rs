@@ -0,0 +1,106 @@
+//! Optional external hook scripts for proxy lifecycle events.
+//!
+//! Hooks are fire-and-forget: a hook that fails to spawn, or whose exit
+//! can't be waited on, is logged via `tracing::warn` and otherwise ignored,
+//! and a hook that never exits only keeps its own detached task waiting, so
+//! a broken hook script can never block or crash the proxy loop.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tracing::warn;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Hooks {
+    /// Run once, right after the listener starts accepting connections.
+    #[serde(default)]
+    on_startup: Option<String>,
+    /// Run for every newly accepted inbound connection, once its upstream
+    /// has been chosen.
+    #[serde(default)]
+    on_connect: Option<String>,
+    /// Run after a connection's bridging loop ends, successfully or not.
+    #[serde(default)]
+    on_disconnect: Option<String>,
+}
+
+impl Hooks {
+    pub fn run_startup(&self, listen_addr: impl ToString) {
+        let Some(command) = &self.on_startup else {
+            return;
+        };
+        run(
+            command,
+            HashMap::from([("TCP_PROXY_LISTEN_ADDR".to_owned(), listen_addr.to_string())]),
+        );
+    }
+
+    pub fn run_connect(&self, client_addr: impl ToString, upstream_addr: impl ToString) {
+        let Some(command) = &self.on_connect else {
+            return;
+        };
+        run(
+            command,
+            HashMap::from([
+                ("TCP_PROXY_CLIENT_ADDR".to_owned(), client_addr.to_string()),
+                (
+                    "TCP_PROXY_UPSTREAM_ADDR".to_owned(),
+                    upstream_addr.to_string(),
+                ),
+            ]),
+        );
+    }
+
+    pub fn run_disconnect(
+        &self,
+        client_addr: impl ToString,
+        upstream_addr: impl ToString,
+        bytes_to_upstream: u64,
+        bytes_to_client: u64,
+    ) {
+        let Some(command) = &self.on_disconnect else {
+            return;
+        };
+        run(
+            command,
+            HashMap::from([
+                ("TCP_PROXY_CLIENT_ADDR".to_owned(), client_addr.to_string()),
+                (
+                    "TCP_PROXY_UPSTREAM_ADDR".to_owned(),
+                    upstream_addr.to_string(),
+                ),
+                (
+                    "TCP_PROXY_BYTES_TO_UPSTREAM".to_owned(),
+                    bytes_to_upstream.to_string(),
+                ),
+                (
+                    "TCP_PROXY_BYTES_TO_CLIENT".to_owned(),
+                    bytes_to_client.to_string(),
+                ),
+            ]),
+        );
+    }
+}
+
+/// Spawns `command` through the shell with `env` set, without blocking the
+/// caller. The child is reaped on a detached task so it doesn't become a
+/// zombie; anything that goes wrong is logged, never propagated.
+fn run(command: &str, env: HashMap<String, String>) {
+    let command = command.to_owned();
+    tokio::spawn(async move {
+        match Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .envs(&env)
+            .spawn()
+        {
+            Ok(mut child) => {
+                if let Err(err) = child.wait().await {
+                    warn!("Hook `{command}` failed to run to completion: {err}");
+                }
+            }
+            Err(err) => warn!("Failed to spawn hook `{command}`: {err}"),
+        }
+    });
+}
@@ -0,0 +1,117 @@
+//! KCP-over-UDP transport, for links that are lossy or high-latency enough
+//! that TCP's head-of-line blocking hurts throughput. A listener can use KCP
+//! on either side independently:
+//!
+//! - Setting `upstream_transport` to `UpstreamTransport::Kcp` dials the
+//!   upstream over KCP instead of `TcpStream::connect`ing to it, and bridges
+//!   it to the (plain TCP) inbound stream.
+//! - Setting `listen_transport` to `UpstreamTransport::Kcp` accepts inbound
+//!   sessions over KCP instead of binding a `TcpListener`, and bridges each
+//!   one to a (plain TCP) upstream. SNI routing isn't available on this
+//!   side, since there's no `TcpStream` to peek a ClientHello from.
+//!
+//! Using KCP on both sides of the same listener at once isn't supported.
+
+use std::{io, net::SocketAddrV4};
+
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tokio_kcp::{KcpConfig, KcpListener, KcpNoDelayConfig, KcpStream};
+
+/// KCP tuning knobs an operator can trade off against each other; see the
+/// KCP protocol docs for what each one means.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct KcpTuning {
+    #[serde(default = "default_true")]
+    pub nodelay: bool,
+    #[serde(default = "default_interval")]
+    pub interval: u32,
+    #[serde(default = "default_resend")]
+    pub resend: u32,
+    #[serde(default = "default_true")]
+    pub no_congestion_control: bool,
+    #[serde(default = "default_window_size")]
+    pub send_window_size: u16,
+    #[serde(default = "default_window_size")]
+    pub recv_window_size: u16,
+}
+
+const fn default_true() -> bool {
+    true
+}
+
+const fn default_interval() -> u32 {
+    10
+}
+
+const fn default_resend() -> u32 {
+    2
+}
+
+const fn default_window_size() -> u16 {
+    256
+}
+
+impl Default for KcpTuning {
+    fn default() -> Self {
+        Self {
+            nodelay: default_true(),
+            interval: default_interval(),
+            resend: default_resend(),
+            no_congestion_control: default_true(),
+            send_window_size: default_window_size(),
+            recv_window_size: default_window_size(),
+        }
+    }
+}
+
+impl From<KcpTuning> for KcpConfig {
+    fn from(tuning: KcpTuning) -> Self {
+        let mut config = Self::default();
+        config.nodelay = KcpNoDelayConfig {
+            nodelay: tuning.nodelay,
+            interval: tuning.interval as i32,
+            resend: tuning.resend as i32,
+            nc: tuning.no_congestion_control,
+        };
+        config.wnd_size = (tuning.send_window_size, tuning.recv_window_size);
+        config
+    }
+}
+
+/// How a listener reaches its upstream, or accepts its inbound connections;
+/// the same `Tcp`/`Kcp` choice applies to either side (`upstream_transport`
+/// and `listen_transport` respectively).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UpstreamTransport {
+    #[default]
+    Tcp,
+    Kcp(KcpTuning),
+}
+
+/// Dials a KCP session to `upstream` and bridges it with `inbound_stream`,
+/// returning the bytes copied in each direction like `copy_bidirectional`.
+pub async fn bridge(
+    inbound_stream: &mut TcpStream,
+    upstream: SocketAddrV4,
+    tuning: KcpTuning,
+) -> io::Result<(u64, u64)> {
+    let mut kcp_stream = KcpStream::connect(&tuning.into(), upstream.into()).await?;
+    tokio::io::copy_bidirectional(inbound_stream, &mut kcp_stream).await
+}
+
+/// Binds a KCP-over-UDP listener on `bind_addr`, for listeners whose
+/// `listen_transport` is `UpstreamTransport::Kcp`.
+pub async fn listen(bind_addr: SocketAddrV4, tuning: KcpTuning) -> io::Result<KcpListener> {
+    KcpListener::bind(tuning.into(), bind_addr.into()).await
+}
+
+/// Bridges an already-accepted inbound KCP session with a plain TCP
+/// upstream connection, the mirror image of `bridge` for the listen side.
+pub async fn bridge_inbound(
+    inbound_stream: &mut KcpStream,
+    outbound_stream: &mut TcpStream,
+) -> io::Result<(u64, u64)> {
+    tokio::io::copy_bidirectional(inbound_stream, outbound_stream).await
+}
@@ -1,4 +1,10 @@
+mod hooks;
+mod kcp;
+mod sni;
+mod transport;
 mod types;
+mod upstream;
+mod wizard;
 
 use std::{error::Error, fs::write, process::ExitCode};
 
@@ -7,8 +13,8 @@ use futures_util::future::join_all;
 use mimalloc::MiMalloc;
 use nix::sys::resource::{getrlimit, setrlimit, Resource};
 use serde_json::to_string_pretty;
-use tracing::{debug, warn};
-use types::{Cli, ProxyConfig, RawConfig};
+use tracing::{debug, error, info, warn};
+use types::{check_raw_configs, Cli, ProxyConfig, RawConfig};
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
@@ -19,6 +25,13 @@ async fn main() -> Result<ExitCode, Box<dyn Error>> {
 
     cli.init_logger();
 
+    if cli.wizard {
+        let raw_configs = wizard::run()?;
+        write("config.json", to_string_pretty(&raw_configs)?)?;
+        info!("Wizard-generated config has been saved to `./config.json`.");
+        return Ok(ExitCode::SUCCESS);
+    }
+
     let Some(config_path) = cli.config_path else {
         let example_raw_config = vec![RawConfig::default()];
         write("config.json", to_string_pretty(&example_raw_config)?)?;
@@ -26,6 +39,18 @@ async fn main() -> Result<ExitCode, Box<dyn Error>> {
         return Ok(ExitCode::FAILURE);
     };
 
+    if cli.check {
+        let errors = check_raw_configs(&RawConfig::read_from_path(&config_path)?);
+        if errors.is_empty() {
+            info!("Config at `{}` is valid.", config_path.display());
+            return Ok(ExitCode::SUCCESS);
+        }
+        for error in &errors {
+            error!("{error}");
+        }
+        return Ok(ExitCode::FAILURE);
+    }
+
     let proxy_config = ProxyConfig::from_raw(&RawConfig::read_from_path(&config_path)?);
 
     let desired_limit = (proxy_config.len() / 10 * 20 + 1) as u64;
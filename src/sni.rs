@@ -0,0 +1,79 @@
+//! TLS ClientHello peeking for SNI-based routing.
+//!
+//! `start_proxy` uses [`peek_server_name`] to look at an inbound connection's
+//! first bytes without consuming them, so a plain TCP connection still sees
+//! those bytes once it is bridged to the upstream.
+
+use std::time::Duration;
+
+use tls_parser::{
+    parse_tls_extensions, parse_tls_plaintext, TlsExtension, TlsMessage, TlsMessageHandshake,
+};
+use tokio::{net::TcpStream, time::timeout};
+use tracing::debug;
+
+/// How long we're willing to wait for enough bytes to show up before giving
+/// up and letting the caller fall back to the default upstream.
+const PEEK_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Large enough to hold a typical ClientHello (including its SNI extension)
+/// without needing to grow the buffer.
+const PEEK_BUFFER_SIZE: usize = 4096;
+
+/// Peeks at `stream`'s inbound bytes and, if they form a TLS ClientHello,
+/// returns the hostname carried in its `server_name` extension.
+///
+/// Returns `None` for anything that isn't a recognizable TLS handshake
+/// (plain TCP, a non-ClientHello record, or a hello without SNI) so the
+/// caller can fall back to the default upstream instead of treating it as an
+/// error.
+pub async fn peek_server_name(stream: &TcpStream) -> Option<String> {
+    let mut buf = [0_u8; PEEK_BUFFER_SIZE];
+
+    let peeked = match timeout(PEEK_TIMEOUT, stream.peek(&mut buf)).await {
+        Ok(Ok(peeked)) => peeked,
+        Ok(Err(err)) => {
+            debug!("Failed to peek inbound stream for SNI: {err}");
+            return None;
+        }
+        Err(_) => {
+            debug!("Timed out waiting for a ClientHello, falling back to the default upstream.");
+            return None;
+        }
+    };
+
+    extract_server_name(&buf[..peeked])
+}
+
+/// Content type byte for a TLS handshake record (RFC 8446 section 5.1).
+const TLS_HANDSHAKE_CONTENT_TYPE: u8 = 0x16;
+
+/// Name type for `host_name` within an SNI extension's server name list
+/// (RFC 6066 section 3). Not to be confused with the SNI extension's own
+/// type id, which is also 0 but a distinct field.
+const SNI_HOST_NAME_TYPE: u16 = 0x00;
+
+fn extract_server_name(record: &[u8]) -> Option<String> {
+    if record.first() != Some(&TLS_HANDSHAKE_CONTENT_TYPE) {
+        return None;
+    }
+
+    let (_, plaintext) = parse_tls_plaintext(record).ok()?;
+    plaintext.msg.iter().find_map(|message| {
+        let TlsMessage::Handshake(TlsMessageHandshake::ClientHello(hello)) = message else {
+            return None;
+        };
+        let extensions = hello.ext?;
+        let (_, extensions) = parse_tls_extensions(extensions).ok()?;
+        extensions.iter().find_map(|extension| {
+            let TlsExtension::SNI(sni_list) = extension else {
+                return None;
+            };
+            sni_list
+                .iter()
+                .find(|(sni_type, _)| *sni_type == SNI_HOST_NAME_TYPE as u8)
+                .and_then(|(_, name)| std::str::from_utf8(name).ok())
+                .map(ToString::to_string)
+        })
+    })
+}
@@ -0,0 +1,103 @@
+//! Alternate framings for a listener's inbound side.
+//!
+//! `start_proxy` normally bridges two raw [`TcpStream`]s with
+//! [`tokio::io::copy_bidirectional`]. When a listener's [`Transport`] is
+//! [`Transport::WebSocket`], the inbound side instead speaks WebSocket (to
+//! tunnel through HTTP-aware middleboxes and reverse proxies) while the
+//! upstream side stays plain TCP.
+
+use std::io;
+
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tokio_tungstenite::tungstenite::{
+    handshake::server::{ErrorResponse, Request, Response},
+    http::StatusCode,
+    Message,
+};
+
+/// How a listener's inbound side is framed.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Transport {
+    /// Raw TCP, bridged with `copy_bidirectional` as before.
+    #[default]
+    Tcp,
+    /// WebSocket on the inbound side; the upstream connection stays plain
+    /// TCP. `path` is the HTTP path the Upgrade request must target.
+    WebSocket { path: String },
+}
+
+/// Performs the server-side WebSocket Upgrade handshake on `inbound_stream`,
+/// then bridges binary WS frames to `outbound_stream` in both directions:
+/// frames read from the socket are written to the upstream, and bytes read
+/// from the upstream are framed back into binary WS messages.
+///
+/// Returns the number of bytes copied in each direction, mirroring
+/// `copy_bidirectional`'s return shape.
+pub async fn bridge(
+    inbound_stream: TcpStream,
+    mut outbound_stream: TcpStream,
+    path: &str,
+) -> io::Result<(u64, u64)> {
+    let ws_stream = tokio_tungstenite::accept_hdr_async(
+        inbound_stream,
+        |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+            if request.uri().path() == path {
+                Ok(response)
+            } else {
+                Err(Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(None)
+                    .expect("building a 404 response with no extra headers cannot fail"))
+            }
+        },
+    )
+    .await
+    .map_err(io::Error::other)?;
+
+    let (mut ws_write, mut ws_read) = ws_stream.split();
+    let (mut tcp_read, mut tcp_write) = outbound_stream.split();
+
+    let to_outbound = async {
+        let mut total = 0_u64;
+        while let Some(message) = ws_read.next().await {
+            match message {
+                Ok(Message::Binary(data)) => {
+                    total += data.len() as u64;
+                    tcp_write.write_all(&data).await?;
+                }
+                Ok(Message::Close(_)) => break,
+                Ok(_) => {}
+                Err(err) => return Err(io::Error::other(err)),
+            }
+        }
+        Ok::<u64, io::Error>(total)
+    };
+
+    let to_inbound = async {
+        let mut total = 0_u64;
+        let mut buf = [0_u8; 8192];
+        loop {
+            let read = tcp_read.read(&mut buf).await?;
+            if read == 0 {
+                break;
+            }
+            total += read as u64;
+            if ws_write
+                .send(Message::Binary(buf[..read].to_vec()))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+        Ok::<u64, io::Error>(total)
+    };
+
+    tokio::try_join!(to_outbound, to_inbound)
+}
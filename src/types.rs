@@ -1,10 +1,10 @@
 #![allow(dead_code)]
 
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeSet, HashMap},
     error::Error,
     fs::File,
-    io::BufReader,
+    io::{self, BufReader},
     iter::zip,
     net::{Ipv4Addr, SocketAddrV4},
     path::{Path, PathBuf},
@@ -12,11 +12,21 @@ use std::{
 
 use clap::Parser;
 use serde::{Deserialize, Serialize};
+use socket2::SockRef;
 use time::macros::{format_description, offset};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+};
 use tracing::{debug, error, info, warn, Level};
 use tracing_subscriber::fmt::time::OffsetTime;
 
+use crate::hooks::Hooks;
+use crate::kcp::{self, KcpTuning, UpstreamTransport};
+use crate::sni::peek_server_name;
+use crate::transport::{self, Transport};
+use crate::upstream::{UpstreamPool, UpstreamStrategy};
+
 #[derive(Parser)]
 #[command(
     version,
@@ -27,6 +37,13 @@ pub struct Cli {
     #[arg(long)]
     /// Display debug logs.
     pub debug: bool,
+    #[arg(long)]
+    /// Run the interactive config wizard instead of loading a config file.
+    pub wizard: bool,
+    #[arg(long)]
+    /// Validate `config_path` and report every invalid entry, without
+    /// binding any sockets.
+    pub check: bool,
 }
 
 impl Cli {
@@ -62,6 +79,66 @@ pub struct RawConfig {
     ip: String,
     port: SourcePortOptions,
     target_port: TargetPortOptions,
+    /// Optional TLS SNI routing table: hostnames (as seen in the
+    /// ClientHello) mapped to the upstream they should be forwarded to.
+    /// Connections whose hostname isn't found here (or that aren't TLS at
+    /// all) still go to `ip`/`port` (and any `additional_upstreams`).
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    sni_routes: HashMap<String, SocketAddrV4>,
+    /// How this listener's inbound side is framed. Defaults to plain TCP.
+    /// Only applies when both `listen_transport` and `upstream_transport`
+    /// are TCP; with KCP on either side the inbound bytes are bridged raw.
+    #[serde(default)]
+    transport: Transport,
+    /// Caps how many connections this listener bridges concurrently.
+    /// Unlimited when unset.
+    #[serde(default)]
+    max_connections: Option<usize>,
+    /// When `max_connections` is reached, drop the new connection instead
+    /// of waiting for a permit to free up. Defaults to waiting.
+    #[serde(default)]
+    reject_when_full: bool,
+    /// `SO_RCVBUF` applied to both the inbound and outbound sockets (TCP
+    /// sockets only; a KCP side keeps the OS default). Left at the OS
+    /// default when unset.
+    #[serde(default)]
+    recv_buffer_size: Option<usize>,
+    /// `SO_SNDBUF` applied to both the inbound and outbound sockets (TCP
+    /// sockets only; a KCP side keeps the OS default). Left at the OS
+    /// default when unset.
+    #[serde(default)]
+    send_buffer_size: Option<usize>,
+    /// Extra upstreams to balance across alongside `ip`/`port`, letting one
+    /// listener spread connections over several backends.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    additional_upstreams: Vec<SocketAddrV4>,
+    /// How to pick among this listener's upstreams when there's more than
+    /// one.
+    #[serde(default)]
+    upstream_strategy: UpstreamStrategy,
+    /// External commands to run on listener startup and connection
+    /// lifecycle events.
+    #[serde(default)]
+    hooks: Hooks,
+    /// Interface to bind the listener to. Defaults to `0.0.0.0` (all
+    /// interfaces), same as before this field existed.
+    #[serde(default)]
+    bind_ip: Option<Ipv4Addr>,
+    /// Address to report in the startup log line, for deployments where
+    /// `bind_ip` isn't what clients actually connect to (e.g. behind NAT).
+    /// Defaults to `bind_ip`.
+    #[serde(default)]
+    advertised_ip: Option<Ipv4Addr>,
+    /// How this listener reaches its upstream(s). Defaults to plain TCP;
+    /// set to KCP for lossy or high-latency upstream links.
+    #[serde(default)]
+    upstream_transport: UpstreamTransport,
+    /// How this listener accepts inbound connections. Defaults to plain
+    /// TCP; set to KCP to accept inbound sessions over KCP instead (e.g.
+    /// the client side of a lossy link). Independent of `upstream_transport`;
+    /// using KCP on both sides of the same listener isn't supported.
+    #[serde(default)]
+    listen_transport: UpstreamTransport,
 }
 
 impl RawConfig {
@@ -69,6 +146,107 @@ impl RawConfig {
         let reader = BufReader::new(File::open(path)?);
         serde_json::from_reader(reader).map_err(Into::into)
     }
+
+    /// Builds an entry from wizard answers; every field the wizard doesn't
+    /// ask about is left at its default.
+    pub fn from_wizard(
+        ip: String,
+        source_start: u16,
+        source_end: u16,
+        target_start: u16,
+        target_end: u16,
+        transport: Transport,
+    ) -> Self {
+        let port = if source_start == source_end {
+            SourcePortOptions::Single(source_start)
+        } else {
+            SourcePortOptions::Range {
+                start: source_start,
+                end: source_end,
+            }
+        };
+        let target_port = if target_start == target_end {
+            TargetPortOptions::Single(target_start)
+        } else {
+            TargetPortOptions::Range {
+                start: target_start,
+                end: target_end,
+            }
+        };
+        Self {
+            ip,
+            port,
+            target_port,
+            transport,
+            ..Self::default()
+        }
+    }
+
+    /// Runs the checks `ProxyConfig::from_raw` uses to decide whether to
+    /// skip an entry, plus the source/target range length check that
+    /// `from_raw` only warns about, and returns every problem found instead
+    /// of skipping the entry. Used by `--check`.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.ip.parse::<Ipv4Addr>().is_err() {
+            errors.push(format!("`{}` is not a valid IPv4 address.", self.ip));
+        }
+
+        match (self.port, self.target_port) {
+            (
+                SourcePortOptions::Range {
+                    start: source_start,
+                    end: source_end,
+                },
+                TargetPortOptions::Range {
+                    start: target_start,
+                    end: target_end,
+                },
+            ) => {
+                if source_start > source_end {
+                    errors.push(format!(
+                        "Source port range ({source_start}-{source_end}) starts after it ends."
+                    ));
+                }
+                if target_start > target_end {
+                    errors.push(format!(
+                        "Target port range ({target_start}-{target_end}) starts after it ends."
+                    ));
+                }
+                if source_start <= source_end
+                    && target_start <= target_end
+                    && source_end - source_start != target_end - target_start
+                {
+                    errors.push(format!(
+                        "Source port range ({source_start}-{source_end}) and target port range ({target_start}-{target_end}) have different lengths."
+                    ));
+                }
+            }
+            (SourcePortOptions::Single(_), TargetPortOptions::Single(_)) => {}
+            _ => errors.push(
+                "Source and target ports must both be a range or both be a single port.".to_owned(),
+            ),
+        }
+
+        errors
+    }
+}
+
+/// Validates every entry in `raw_config_list`, returning one message per
+/// problem found, each prefixed with the entry's index so `--check` output
+/// can point at the offending config.
+pub fn check_raw_configs(raw_config_list: &[RawConfig]) -> Vec<String> {
+    raw_config_list
+        .iter()
+        .enumerate()
+        .flat_map(|(index, raw_config)| {
+            raw_config
+                .validate()
+                .into_iter()
+                .map(move |error| format!("Entry #{index} (IP {}): {error}", raw_config.ip))
+        })
+        .collect()
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -107,37 +285,85 @@ impl Default for TargetPortOptions {
 pub struct ProxyConfig {
     pub source_addr: SocketAddrV4,
     pub target_addr: SocketAddrV4,
+    /// SNI hostname -> upstream overrides for this listener. Empty unless
+    /// the matching `RawConfig` declared `sni_routes`.
+    pub routes: HashMap<String, SocketAddrV4>,
+    /// How this listener's inbound side is framed.
+    pub transport: Transport,
+    /// Bounds how many connections this listener bridges concurrently.
+    /// `None` means unlimited.
+    pub connection_permits: Option<Semaphore>,
+    /// When out of permits, drop the connection instead of waiting for one.
+    pub reject_when_full: bool,
+    pub recv_buffer_size: Option<usize>,
+    pub send_buffer_size: Option<usize>,
+    /// This listener's upstream(s). Always contains at least `source_addr`;
+    /// `additional_upstreams` extends it into a load-balanced, health-aware
+    /// pool.
+    pub upstream_pool: UpstreamPool,
+    pub hooks: Hooks,
+    /// Address to report in logs for this listener; equal to `target_addr`
+    /// unless `advertised_ip` overrides the IP.
+    pub advertised_addr: SocketAddrV4,
+    pub upstream_transport: UpstreamTransport,
+    pub listen_transport: UpstreamTransport,
 }
 
 impl ProxyConfig {
-    const fn new((source_addr, target_addr): (SocketAddrV4, SocketAddrV4)) -> Self {
+    fn new(
+        (source_addr, target_addr): (SocketAddrV4, SocketAddrV4),
+        raw_config: &RawConfig,
+    ) -> Self {
+        let mut upstreams = vec![source_addr];
+        upstreams.extend(raw_config.additional_upstreams.iter().copied());
+
         Self {
             source_addr,
             target_addr,
+            routes: raw_config.sni_routes.clone(),
+            transport: raw_config.transport.clone(),
+            connection_permits: raw_config.max_connections.map(Semaphore::new),
+            reject_when_full: raw_config.reject_when_full,
+            recv_buffer_size: raw_config.recv_buffer_size,
+            send_buffer_size: raw_config.send_buffer_size,
+            upstream_pool: UpstreamPool::new(upstreams, raw_config.upstream_strategy),
+            hooks: raw_config.hooks.clone(),
+            advertised_addr: SocketAddrV4::new(
+                raw_config.advertised_ip.unwrap_or(*target_addr.ip()),
+                target_addr.port(),
+            ),
+            upstream_transport: raw_config.upstream_transport,
+            listen_transport: raw_config.listen_transport,
         }
     }
 
     #[rustfmt::skip]
     pub fn from_raw(raw_config_list: &[RawConfig]) -> Vec<Self> {
-        let target_ip = Ipv4Addr::new(0, 0, 0, 0);
+        let mut raw_by_target: HashMap<SocketAddrV4, RawConfig> = HashMap::new();
         raw_config_list
             .iter()
             .filter_map(|raw_config| {
                 let Ok(source_ip) = raw_config.ip.parse() else {
                     return None;
                 };
-                match (raw_config.port, raw_config.target_port) {
+                let target_ip = raw_config.bind_ip.unwrap_or(Ipv4Addr::new(0, 0, 0, 0));
+                let pairs = match (raw_config.port, raw_config.target_port) {
                     (
                         SourcePortOptions::Range { start: source_start, end: source_end },
                         TargetPortOptions::Range { start: target_start, end: target_end },
                     ) => {
-                        if source_end - source_start != target_end - target_start {
-                            warn!("IP {}'s source ports and target ports has different lengths, some port will not be exposed.", raw_config.ip);
+                        if source_start > source_end || target_start > target_end {
+                            error!("IP {}'s port range starts after it ends, the setup process for this IP will be skipped.", raw_config.ip);
+                            None
+                        } else {
+                            if source_end - source_start != target_end - target_start {
+                                warn!("IP {}'s source ports and target ports has different lengths, some port will not be exposed.", raw_config.ip);
+                            }
+                            let result = zip(source_start..=source_end, target_start..=target_end)
+                                .map(|(source_port, target_port)| (SocketAddrV4::new(source_ip, source_port), SocketAddrV4::new(target_ip, target_port)))
+                                .collect();
+                            Some(result)
                         }
-                        let result = zip(source_start..=source_end, target_start..=target_end)
-                            .map(|(source_port, target_port)| (SocketAddrV4::new(source_ip, source_port), SocketAddrV4::new(target_ip, target_port)))
-                            .collect();
-                        Some(result)
                     }
                     (
                         SourcePortOptions::Single(source_port),
@@ -150,38 +376,252 @@ impl ProxyConfig {
                         error!("IP {}'s port option is invalid, the setup process for this IP will be skipped.", raw_config.ip);
                         None
                     },
+                };
+                if let Some(pairs) = &pairs {
+                    for (_, target_addr) in pairs {
+                        if raw_by_target.contains_key(target_addr) {
+                            warn!("Multiple config entries bind {target_addr}; only the settings from the entry for IP {} will apply to it (they would also fail to bind concurrently).", raw_config.ip);
+                        }
+                        raw_by_target.insert(*target_addr, raw_config.clone());
+                    }
                 }
+                pairs
             })
             .flatten()
             .collect::<BTreeSet<(SocketAddrV4, SocketAddrV4)>>()
             .into_iter()
-            .map(Self::new)
+            .map(|pair| {
+                let raw_config = raw_by_target.get(&pair.1).cloned().unwrap_or_default();
+                Self::new(pair, &raw_config)
+            })
             .collect()
     }
 
+    /// Looks for an explicit SNI route for a freshly accepted connection:
+    /// if the inbound stream's TLS ClientHello carries a hostname present
+    /// in `self.routes`, returns its upstream. Returns `None` (plain TCP,
+    /// unmatched hostname, or no routes configured) when the connection
+    /// should go through `self.upstream_pool` instead.
+    async fn select_route(&self, inbound_stream: &TcpStream) -> Option<SocketAddrV4> {
+        if self.routes.is_empty() {
+            return None;
+        }
+
+        let server_name = peek_server_name(inbound_stream).await?;
+        match self.routes.get(&server_name) {
+            Some(upstream) => Some(*upstream),
+            None => {
+                debug!("No route for SNI `{server_name}`, falling back to the upstream pool.");
+                None
+            }
+        }
+    }
+
+    /// Applies the configured `SO_RCVBUF`/`SO_SNDBUF` sizes to `stream`.
+    /// A no-op when neither was set.
+    fn configure_socket(&self, stream: &TcpStream) -> io::Result<()> {
+        let socket_ref = SockRef::from(stream);
+        if let Some(size) = self.recv_buffer_size {
+            socket_ref.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket_ref.set_send_buffer_size(size)?;
+        }
+        Ok(())
+    }
+
     pub async fn start_proxy(&'static self) -> Result<(), Box<dyn Error>> {
+        self.hooks.run_startup(self.target_addr);
+        match &self.listen_transport {
+            UpstreamTransport::Tcp => self.run_tcp_listener().await,
+            UpstreamTransport::Kcp(tuning) => self.run_kcp_listener(*tuning).await,
+        }
+    }
+
+    /// Accepts plain TCP connections on `target_addr` and bridges each one
+    /// to this listener's upstream(s), per `upstream_transport`/`transport`.
+    async fn run_tcp_listener(&'static self) -> Result<(), Box<dyn Error>> {
         let listener = TcpListener::bind(self.target_addr).await?;
         info!(
             "Proxy for {} started, bind as {}.",
-            self.source_addr, self.target_addr
+            self.source_addr, self.advertised_addr
         );
+        self.hooks.run_startup(self.target_addr);
         while let Ok((mut inbound_stream, client_addr)) = listener.accept().await {
+            let permit = match &self.connection_permits {
+                Some(semaphore) if self.reject_when_full => match semaphore.try_acquire() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => {
+                        debug!("Rejecting {client_addr}: no free connection slot.");
+                        continue;
+                    }
+                },
+                Some(semaphore) => Some(
+                    semaphore
+                        .acquire()
+                        .await
+                        .expect("connection semaphore is never closed"),
+                ),
+                None => None,
+            };
+
             tokio::spawn(async move {
+                let _permit = permit;
                 debug!("New user: {client_addr}");
-                let mut outbound_stream = TcpStream::connect(self.source_addr).await?;
-                match tokio::io::copy_bidirectional(&mut inbound_stream, &mut outbound_stream).await
-                {
+                if let Err(err) = self.configure_socket(&inbound_stream) {
+                    warn!("Failed to apply socket buffer sizes to {client_addr}: {err}");
+                }
+                let route = self.select_route(&inbound_stream).await;
+                let pool_handle = if route.is_none() {
+                    Some(self.upstream_pool.select().await)
+                } else {
+                    None
+                };
+                let upstream = route.unwrap_or_else(|| pool_handle.as_ref().unwrap().addr());
+                self.hooks.run_connect(client_addr, upstream);
+
+                let result = match &self.upstream_transport {
+                    UpstreamTransport::Kcp(tuning) => {
+                        let result = kcp::bridge(&mut inbound_stream, upstream, *tuning).await;
+                        match (&result, &pool_handle) {
+                            (Ok(_), Some(handle)) => handle.record_success().await,
+                            (Err(_), Some(handle)) => handle.record_failure().await,
+                            _ => {}
+                        }
+                        result
+                    }
+                    UpstreamTransport::Tcp => {
+                        let outbound_stream = match TcpStream::connect(upstream).await {
+                            Ok(stream) => {
+                                if let Some(handle) = &pool_handle {
+                                    handle.record_success().await;
+                                }
+                                stream
+                            }
+                            Err(err) => {
+                                if let Some(handle) = &pool_handle {
+                                    handle.record_failure().await;
+                                }
+                                return Err(err.into());
+                            }
+                        };
+                        if let Err(err) = self.configure_socket(&outbound_stream) {
+                            warn!(
+                                "Failed to apply socket buffer sizes for upstream {upstream}: {err}"
+                            );
+                        }
+
+                        match &self.transport {
+                            Transport::Tcp => {
+                                let mut outbound_stream = outbound_stream;
+                                tokio::io::copy_bidirectional(
+                                    &mut inbound_stream,
+                                    &mut outbound_stream,
+                                )
+                                .await
+                            }
+                            Transport::WebSocket { path } => {
+                                transport::bridge(inbound_stream, outbound_stream, path).await
+                            }
+                        }
+                    }
+                };
+
+                let (to_outbound, to_inbound) = match result {
                     Ok((to_outbound, to_inbound)) => {
                         debug!("Processed {to_outbound} bytes from client, {to_inbound} bytes from server.");
+                        (to_outbound, to_inbound)
                     }
                     Err(err) => {
                         warn!("Error while proxying: {}", err);
+                        (0, 0)
                     }
-                }
+                };
+                self.hooks
+                    .run_disconnect(client_addr, upstream, to_outbound, to_inbound);
                 Ok::<(), Box<dyn Error + Sync + Send + 'static>>(())
             });
         }
 
         Ok(())
     }
+
+    /// Accepts KCP-over-UDP sessions on `target_addr` and bridges each one
+    /// to a plain TCP upstream. SNI routing isn't available here (there's
+    /// no `TcpStream` to peek a ClientHello from), so every connection goes
+    /// through `upstream_pool`; `upstream_transport` is ignored, since
+    /// using KCP on both sides of the same listener isn't supported.
+    async fn run_kcp_listener(&'static self, tuning: KcpTuning) -> Result<(), Box<dyn Error>> {
+        let listener = kcp::listen(self.target_addr, tuning).await?;
+        info!(
+            "Proxy for {} started, bind as {} (KCP).",
+            self.source_addr, self.advertised_addr
+        );
+        loop {
+            let (mut inbound_stream, client_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!(
+                        "Failed to accept a KCP session on {}: {err}",
+                        self.target_addr
+                    );
+                    continue;
+                }
+            };
+
+            let permit = match &self.connection_permits {
+                Some(semaphore) if self.reject_when_full => match semaphore.try_acquire() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => {
+                        debug!("Rejecting {client_addr}: no free connection slot.");
+                        continue;
+                    }
+                },
+                Some(semaphore) => Some(
+                    semaphore
+                        .acquire()
+                        .await
+                        .expect("connection semaphore is never closed"),
+                ),
+                None => None,
+            };
+
+            tokio::spawn(async move {
+                let _permit = permit;
+                debug!("New user: {client_addr}");
+                let pool_handle = self.upstream_pool.select().await;
+                let upstream = pool_handle.addr();
+                self.hooks.run_connect(client_addr, upstream);
+
+                let result = match TcpStream::connect(upstream).await {
+                    Ok(mut outbound_stream) => {
+                        pool_handle.record_success().await;
+                        if let Err(err) = self.configure_socket(&outbound_stream) {
+                            warn!(
+                                "Failed to apply socket buffer sizes for upstream {upstream}: {err}"
+                            );
+                        }
+                        kcp::bridge_inbound(&mut inbound_stream, &mut outbound_stream).await
+                    }
+                    Err(err) => {
+                        pool_handle.record_failure().await;
+                        Err(err)
+                    }
+                };
+
+                let (to_outbound, to_inbound) = match result {
+                    Ok((to_outbound, to_inbound)) => {
+                        debug!("Processed {to_outbound} bytes from client, {to_inbound} bytes from server.");
+                        (to_outbound, to_inbound)
+                    }
+                    Err(err) => {
+                        warn!("Error while proxying: {}", err);
+                        (0, 0)
+                    }
+                };
+                self.hooks
+                    .run_disconnect(client_addr, upstream, to_outbound, to_inbound);
+            });
+        }
+    }
 }
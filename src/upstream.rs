@@ -0,0 +1,152 @@
+//! Health-aware upstream selection for listeners with more than one
+//! backend.
+//!
+//! A [`UpstreamPool`] tracks, per upstream, how many connection attempts
+//! have failed in a row. After too many consecutive failures an upstream is
+//! temporarily banned (excluded from selection) for a cooldown window, and
+//! automatically re-admitted once that window elapses. If every upstream in
+//! the pool is currently banned, selection falls back to trying them anyway
+//! so the proxy self-heals once connectivity returns.
+
+use std::{
+    net::SocketAddrV4,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+/// How a listener picks among its upstreams for each new connection.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamStrategy {
+    #[default]
+    RoundRobin,
+    LeastConnections,
+}
+
+/// Consecutive connection failures an upstream tolerates before it's
+/// temporarily excluded from selection.
+const BAN_THRESHOLD: u32 = 3;
+
+/// How long a banned upstream stays excluded before being re-admitted.
+const BAN_COOLDOWN: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+struct Upstream {
+    addr: SocketAddrV4,
+    consecutive_failures: AtomicU32,
+    active_connections: AtomicUsize,
+    banned_until: Mutex<Option<Instant>>,
+}
+
+impl Upstream {
+    fn new(addr: SocketAddrV4) -> Self {
+        Self {
+            addr,
+            consecutive_failures: AtomicU32::new(0),
+            active_connections: AtomicUsize::new(0),
+            banned_until: Mutex::new(None),
+        }
+    }
+
+    async fn is_banned(&self) -> bool {
+        match *self.banned_until.lock().await {
+            Some(until) => Instant::now() < until,
+            None => false,
+        }
+    }
+
+    async fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        *self.banned_until.lock().await = None;
+    }
+
+    async fn record_failure(&self) {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= BAN_THRESHOLD {
+            *self.banned_until.lock().await = Some(Instant::now() + BAN_COOLDOWN);
+        }
+    }
+}
+
+/// A listener's pool of candidate upstreams, plus the state needed to
+/// balance across and fail over between them.
+#[derive(Debug)]
+pub struct UpstreamPool {
+    upstreams: Vec<Upstream>,
+    strategy: UpstreamStrategy,
+    next: AtomicUsize,
+}
+
+impl UpstreamPool {
+    pub fn new(addrs: Vec<SocketAddrV4>, strategy: UpstreamStrategy) -> Self {
+        Self {
+            upstreams: addrs.into_iter().map(Upstream::new).collect(),
+            strategy,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Picks the next upstream to try, skipping banned ones unless every
+    /// upstream in the pool is currently banned.
+    pub async fn select(&self) -> UpstreamHandle<'_> {
+        let mut candidates = Vec::with_capacity(self.upstreams.len());
+        for (index, upstream) in self.upstreams.iter().enumerate() {
+            if !upstream.is_banned().await {
+                candidates.push(index);
+            }
+        }
+        if candidates.is_empty() {
+            candidates.extend(0..self.upstreams.len());
+        }
+
+        let chosen = match self.strategy {
+            UpstreamStrategy::RoundRobin => {
+                let index = self.next.fetch_add(1, Ordering::Relaxed) % candidates.len();
+                candidates[index]
+            }
+            UpstreamStrategy::LeastConnections => *candidates
+                .iter()
+                .min_by_key(|&&index| {
+                    self.upstreams[index]
+                        .active_connections
+                        .load(Ordering::Relaxed)
+                })
+                .expect("candidates is non-empty"),
+        };
+
+        let upstream = &self.upstreams[chosen];
+        upstream.active_connections.fetch_add(1, Ordering::Relaxed);
+        UpstreamHandle { upstream }
+    }
+}
+
+/// Tracks one in-flight connection's use of its chosen upstream so
+/// `active_connections` stays accurate for the lifetime of the bridge.
+pub struct UpstreamHandle<'a> {
+    upstream: &'a Upstream,
+}
+
+impl UpstreamHandle<'_> {
+    pub fn addr(&self) -> SocketAddrV4 {
+        self.upstream.addr
+    }
+
+    pub async fn record_success(&self) {
+        self.upstream.record_success().await;
+    }
+
+    pub async fn record_failure(&self) {
+        self.upstream.record_failure().await;
+    }
+}
+
+impl Drop for UpstreamHandle<'_> {
+    fn drop(&mut self) {
+        self.upstream
+            .active_connections
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
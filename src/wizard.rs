@@ -0,0 +1,96 @@
+//! Interactive config generator for `--wizard`.
+//!
+//! Prompts for an upstream IP, source/target port ranges, and transport,
+//! rejecting answers `ProxyConfig::from_raw` would otherwise have to warn
+//! about or skip (e.g. an invalid IP or mismatched range lengths) before they
+//! ever reach a config file.
+
+use std::{
+    io::{self, Write},
+    net::Ipv4Addr,
+};
+
+use crate::transport::Transport;
+use crate::types::RawConfig;
+
+pub fn run() -> io::Result<Vec<RawConfig>> {
+    let mut entries = Vec::new();
+
+    loop {
+        let ip = prompt("Upstream IP")?;
+        if ip.parse::<Ipv4Addr>().is_err() {
+            println!("`{ip}` is not a valid IPv4 address, please try this entry again.\n");
+            continue;
+        }
+        let (source_start, source_end) = prompt_port_range("Source")?;
+        let (target_start, target_end) = prompt_port_range("Target")?;
+
+        if source_end - source_start != target_end - target_start {
+            println!("Source and target port ranges must have the same length, please try this entry again.\n");
+            continue;
+        }
+
+        let transport = prompt_transport()?;
+
+        entries.push(RawConfig::from_wizard(
+            ip,
+            source_start,
+            source_end,
+            target_start,
+            target_end,
+            transport,
+        ));
+
+        if !prompt_yes_no("Add another entry?")? {
+            break;
+        }
+    }
+
+    Ok(entries)
+}
+
+fn prompt(label: &str) -> io::Result<String> {
+    print!("{label}: ");
+    io::stdout().flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(input.trim().to_owned())
+}
+
+fn prompt_port_range(label: &str) -> io::Result<(u16, u16)> {
+    loop {
+        let start = prompt(&format!("{label} port range start"))?;
+        let end = prompt(&format!(
+            "{label} port range end (same as start for a single port)"
+        ))?;
+        match (start.parse(), end.parse()) {
+            (Ok(start), Ok(end)) if start <= end => return Ok((start, end)),
+            _ => println!("Enter two valid ports with start <= end.\n"),
+        }
+    }
+}
+
+fn prompt_transport() -> io::Result<Transport> {
+    loop {
+        let answer = prompt("Transport (tcp/websocket)")?;
+        match answer.to_lowercase().as_str() {
+            "" | "tcp" => return Ok(Transport::Tcp),
+            "websocket" | "ws" => {
+                let path = prompt("WebSocket upgrade path (e.g. /ws)")?;
+                return Ok(Transport::WebSocket { path });
+            }
+            _ => println!("Enter `tcp` or `websocket`.\n"),
+        }
+    }
+}
+
+fn prompt_yes_no(label: &str) -> io::Result<bool> {
+    loop {
+        let answer = prompt(&format!("{label} (y/N)"))?;
+        match answer.to_lowercase().as_str() {
+            "y" | "yes" => return Ok(true),
+            "" | "n" | "no" => return Ok(false),
+            _ => println!("Enter `y` or `n`.\n"),
+        }
+    }
+}